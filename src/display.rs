@@ -1,7 +1,15 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fmt::{Display, Formatter, Result};
 
 use types::*;
 
+thread_local!{
+    // Variable ids currently being printed, so a cyclic binding renders as
+    // `...` instead of recursing forever.
+    static VISITING: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+}
+
 impl Display for Atom {
     fn fmt(&self, f: &mut Formatter) -> Result {
         write!(f, "{}", self.name)
@@ -14,17 +22,74 @@ impl Display for Variable {
         match *self.assignment.borrow() {
             None => write!(f, "[None]"),
             Some(ref term) => {
+                // guard against cyclic bindings built in occurs-check-off mode
+                let recursing = VISITING.with(|v| !v.borrow_mut().insert(self.id));
                 write!(f, "[")?;
-                term.fmt(f)?;
+                let result = if recursing {
+                    write!(f, "...")
+                } else {
+                    term.fmt(f)
+                };
+                if !recursing {
+                    VISITING.with(|v| {
+                        v.borrow_mut().remove(&self.id);
+                    });
+                }
+                result?;
                 write!(f, "]")
             },
         }
     }
 }
 
+// Is this the `'.'(Head, Tail)` cons functor?
+fn is_cons(pred: &Predicate) -> bool {
+    pred.name.name == "." && pred.arguments.to_vec().len() == 2
+}
+
+// Print a proper list starting at a cons cell in `[a, b | Tail]` form.
+fn fmt_list(pred: &Predicate, f: &mut Formatter) -> Result {
+    write!(f, "[")?;
+    let mut current = Term::Pred(pred.clone());
+    let mut first = true;
+    loop {
+        current = match current {
+            Term::Pred(p) => {
+                if is_cons(&p) {
+                    let mut args = p.arguments.to_vec();
+                    let tail = args.pop().unwrap();
+                    let head = args.pop().unwrap();
+                    if !first {
+                        write!(f, ", ")?;
+                    }
+                    first = false;
+                    write!(f, "{}", head)?;
+                    tail
+                } else if p.name.name == "[]" {
+                    break;
+                } else {
+                    write!(f, " | {}", Term::Pred(p))?;
+                    break;
+                }
+            }
+            other => {
+                write!(f, " | {}", other)?;
+                break;
+            }
+        };
+    }
+    write!(f, "]")
+}
+
 impl Display for Predicate {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        write!(f, "{}({})", self.name, self.arguments)
+        if self.name.name == "[]" && self.arguments.to_vec().is_empty() {
+            write!(f, "[]")
+        } else if is_cons(self) {
+            fmt_list(self, f)
+        } else {
+            write!(f, "{}({})", self.name, self.arguments)
+        }
     }
 }
 
@@ -59,7 +124,8 @@ impl Display for Term {
         match self {
             &Var(ref var) => var.fmt(f),
             &Pred(ref pred) => pred.fmt(f),
-            &List(ref list) => list.fmt(f),
+            &Num(n) => write!(f, "{}", n),
+            &Clause(ref clause) => clause.fmt(f),
         }
     }
 }
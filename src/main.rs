@@ -1,4 +1,10 @@
-use std::io::{self, BufRead};
+extern crate clap;
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use clap::{App, AppSettings, Arg, SubCommand};
 
 mod types;
 mod parser;
@@ -7,34 +13,252 @@ mod display;
 use types::*;
 use parser::*;
 
-fn main() {
-    set_debug(true);
-    let mut knowledge = vec![];
+fn run_command(command: Command, knowledge: &mut Vec<Clause>, quiet: bool) {
+    match command {
+        Command::Assertion(assertion) => {
+            if !quiet {
+                println!("accepted: {}", assertion);
+            }
+            match assertion {
+                Term::Pred(p) => knowledge.push(Clause {
+                    result: p,
+                    conditions: List::Nil,
+                }),
+                Term::Clause(c) => knowledge.push(c),
+                other => println!("cannot assert: {}", other),
+            }
+        }
+        Command::Directive(goal) => run_directive(goal, knowledge, quiet),
+        Command::Question(question) => {
+            println!("asked: {}", question);
+            // Snapshot every solution as it is found, because the trail is
+            // rolled back as the search backtracks to the next answer.
+            let vars: Vec<_> = question.variables().into_iter().collect();
+            let goals = vec![question];
+            let mut solutions: Vec<String> = vec![];
+            solve(&goals, &knowledge, &mut || {
+                let mut s = String::new();
+                for v in vars.iter() {
+                    s.push_str(&format!("  {}\n", v));
+                }
+                solutions.push(s);
+                // keep searching so every solution is enumerated
+                false
+            });
+            if solutions.is_empty() {
+                println!("false");
+            } else {
+                println!("true");
+                for (i, solution) in solutions.iter().enumerate() {
+                    if i > 0 {
+                        println!(";");
+                    }
+                    print!("{}", solution);
+                }
+            }
+        }
+    }
+}
+
+// Directives are run for their effect. `op/3` mutates the operator table and
+// `set_occurs_check/1` toggles the occurs check; anything else is executed as
+// a one-shot goal.
+fn run_directive(goal: Term, knowledge: &[Clause], quiet: bool) {
+    if let Term::Pred(ref p) = goal {
+        if p.name.name == "op" {
+            let args = p.arguments.to_vec();
+            if let [Term::Num(priority), Term::Pred(ty), Term::Pred(name)] = &args[..] {
+                match register_op(*priority as u32, &ty.name.name, &name.name.name) {
+                    Ok(()) => if !quiet {
+                        println!("accepted: {}", goal);
+                    },
+                    Err(error) => println!("op/3: {}", error),
+                }
+            } else {
+                println!("op/3: expected op(Priority, Type, Name)");
+            }
+            return;
+        }
+        if p.name.name == "set_occurs_check" {
+            if let [Term::Pred(flag)] = &p.arguments.to_vec()[..] {
+                set_occurs_check(flag.name.name == "true");
+                if !quiet {
+                    println!("accepted: {}", goal);
+                }
+            } else {
+                println!("set_occurs_check/1: expected true or false");
+            }
+            return;
+        }
+    }
+
+    let goals = vec![goal];
+    if solve(&goals, knowledge, &mut || true) {
+        println!("true");
+    } else {
+        println!("false");
+    }
+}
+
+fn prompt(s: &str) {
+    print!("{}", s);
+    io::stdout().flush().ok();
+}
+
+// Report the 1-based line of a char offset for file diagnostics.
+fn line_of(source: &str, offset: usize) -> usize {
+    1 + source.chars().take(offset).filter(|&c| c == '\n').count()
+}
+
+// Load every clause in a file into `knowledge`, one at a time, so an invalid
+// clause only discards itself: every clause parsed before or after it still
+// loads, and each failure is reported against its own file and line.
+// `quiet` suppresses the REPL's per-clause "accepted: ..." echo, for the
+// non-interactive `query` subcommand.
+fn load_file(path: &str, knowledge: &mut Vec<Clause>, quiet: bool) {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            return;
+        }
+    };
+
+    for result in parse_commands(&source) {
+        match result {
+            Ok(command) => run_command(command, knowledge, quiet),
+            Err(diag) => {
+                eprintln!("{}:{}: {}", path, line_of(&source, diag.span.0), diag.message);
+                eprintln!("{}", render_error(&source, &diag));
+            }
+        }
+    }
+}
 
+// Run a single goal string (with its trailing `.`) against the loaded program,
+// printing any bindings and reporting whether it succeeded.
+fn run_query(goal: &str, knowledge: &[Clause]) -> bool {
+    let input = format!("?- {}", goal);
+    let commands = match parse_terms(input.chars()) {
+        Ok(commands) => commands,
+        Err(ParseError::Invalid(diag)) => {
+            eprintln!("{}", render_error(&input, &diag));
+            return false;
+        }
+        Err(ParseError::Incomplete) => {
+            eprintln!("incomplete goal (missing `.`?)");
+            return false;
+        }
+    };
+
+    let mut succeeded = false;
+    for command in commands {
+        if let Command::Question(question) = command {
+            let vars: Vec<_> = question.variables().into_iter().collect();
+            let goals = vec![question];
+            solve(&goals, knowledge, &mut || {
+                for v in vars.iter() {
+                    println!("{}", v);
+                }
+                succeeded = true;
+                // a one-shot query stops at the first solution
+                true
+            });
+        }
+    }
+    println!("{}", if succeeded { "true" } else { "false" });
+    succeeded
+}
+
+// The interactive read loop: accumulate lines until the parser reports a run
+// of complete commands; a partial term keeps us reading with a continuation
+// prompt.
+fn repl(mut knowledge: Vec<Clause>) {
     let stdin = io::stdin();
     let stdin = stdin.lock();
+
+    let mut buffer = String::new();
+    prompt("?- ");
     for line in stdin.lines() {
-        if let Ok(line) = line {
-            if let Ok(result) = parse_line(&mut line.chars().peekable()) {
-                match result {
-                    Command::Assertion(assertion) => {
-                        println!("accepted: {}", assertion);
-                        knowledge.push(assertion)
-                    }
-                    Command::Question(question) => {
-                        println!("asked: {}", question);
-                        match question.derive(&knowledge) {
-                            Err(error) => println!("false: {}", error),
-                            Ok(subst) => {
-                                println!("true");
-                                for v in subst.iter() {
-                                    println!("  {}", v);
-                                }
-                            }
-                        }
-                    }
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        buffer.push_str(&line);
+        buffer.push('\n');
+
+        match parse_terms(buffer.chars()) {
+            Err(ParseError::Incomplete) => {
+                prompt("|  ");
+                continue;
+            }
+            Err(ParseError::Invalid(diag)) => {
+                println!("{}", render_error(&buffer, &diag));
+                buffer.clear();
+            }
+            Ok(commands) => {
+                for command in commands {
+                    run_command(command, &mut knowledge, false);
                 }
+                buffer.clear();
+            }
+        }
+        prompt("?- ");
+    }
+}
+
+fn main() {
+    let matches = App::new("prolog")
+        .about("A small Prolog interpreter")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("debug")
+                .short("d")
+                .long("debug")
+                .global(true)
+                .help("Trace derivation"),
+        )
+        .subcommand(
+            SubCommand::with_name("consult")
+                .about("Load programs, then start the interactive REPL")
+                .arg(
+                    Arg::with_name("files")
+                        .required(true)
+                        .multiple(true)
+                        .help("Program files to load"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("query")
+                .about("Load a program and run a single goal non-interactively")
+                .arg(Arg::with_name("file").required(true).help("Program file to load"))
+                .arg(
+                    Arg::with_name("goal")
+                        .short("g")
+                        .long("goal")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Goal to run, terminated by `.`"),
+                ),
+        )
+        .get_matches();
+
+    set_debug(matches.is_present("debug"));
+
+    match matches.subcommand() {
+        ("consult", Some(sub)) => {
+            let mut knowledge = Vec::new();
+            for file in sub.values_of("files").unwrap() {
+                load_file(file, &mut knowledge, false);
             }
+            repl(knowledge);
+        }
+        ("query", Some(sub)) => {
+            let mut knowledge = Vec::new();
+            load_file(sub.value_of("file").unwrap(), &mut knowledge, true);
+            let succeeded = run_query(sub.value_of("goal").unwrap(), &knowledge);
+            process::exit(if succeeded { 0 } else { 1 });
         }
+        _ => unreachable!(),
     }
 }
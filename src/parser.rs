@@ -1,26 +1,196 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::iter::Peekable;
 
 use types::*;
 
-type ParseError = ();
+/// A single-label source annotation: what went wrong and where.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    /// Half-open char offsets `[start, end)` into the source buffer.
+    pub span: (usize, usize),
+}
+
+/// Result of trying to read a term out of a character stream.
+///
+/// The REPL needs to tell two failure modes apart: a buffer that merely
+/// stopped in the middle of a term (and should be completed by reading more
+/// lines) versus input that can never become a valid term, which carries a
+/// spanned diagnostic for rendering.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The stream ended while a term was still expected.
+    Incomplete,
+    /// The stream contains something that is not a valid term.
+    Invalid(Diagnostic),
+}
+
+fn invalid(span: (usize, usize), message: &str) -> ParseError {
+    ParseError::Invalid(Diagnostic {
+        message: message.into(),
+        span: span,
+    })
+}
+
 type ParseResult = Result<Term, ParseError>;
 
 #[derive(Debug)]
 pub enum Command {
     Assertion(Term),
     Question(Term),
+    Directive(Term),
+}
+
+/// The associativity/fixity classes of the standard operator notation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OpType {
+    Xfx,
+    Xfy,
+    Yfx,
+    Fy,
+    Fx,
+}
+
+// A name may act as both a prefix and an infix operator (e.g. `-`).
+#[derive(Clone, Copy, Default)]
+struct OpDef {
+    infix: Option<(u32, OpType)>,
+    prefix: Option<(u32, OpType)>,
+}
+
+thread_local!{
+    // The operator table is mutable global state, registered through `op/3`
+    // before use, exactly like the debug flag in `types`.
+    static OPERATORS: RefCell<HashMap<String, OpDef>> = RefCell::new(default_operators());
+}
+
+fn set_infix(m: &mut HashMap<String, OpDef>, name: &str, priority: u32, ty: OpType) {
+    m.entry(name.to_string()).or_insert_with(OpDef::default).infix = Some((priority, ty));
+}
+
+fn set_prefix(m: &mut HashMap<String, OpDef>, name: &str, priority: u32, ty: OpType) {
+    m.entry(name.to_string()).or_insert_with(OpDef::default).prefix = Some((priority, ty));
+}
+
+fn default_operators() -> HashMap<String, OpDef> {
+    use OpType::*;
+    let mut m = HashMap::new();
+    set_infix(&mut m, ":-", 1200, Xfx);
+    set_infix(&mut m, ",", 1000, Xfy);
+    set_infix(&mut m, "=", 700, Xfx);
+    set_infix(&mut m, "is", 700, Xfx);
+    set_infix(&mut m, "<", 700, Xfx);
+    set_infix(&mut m, ">", 700, Xfx);
+    set_infix(&mut m, "=:=", 700, Xfx);
+    set_infix(&mut m, "+", 500, Yfx);
+    set_infix(&mut m, "-", 500, Yfx);
+    set_infix(&mut m, "*", 400, Yfx);
+    set_infix(&mut m, "/", 400, Yfx);
+    set_infix(&mut m, "mod", 400, Yfx);
+    set_prefix(&mut m, "-", 200, Fy);
+    set_prefix(&mut m, "+", 200, Fy);
+    m
+}
+
+fn parse_optype(s: &str) -> Result<OpType, String> {
+    use OpType::*;
+    match s {
+        "xfx" => Ok(Xfx),
+        "xfy" => Ok(Xfy),
+        "yfx" => Ok(Yfx),
+        "fy" => Ok(Fy),
+        "fx" => Ok(Fx),
+        other => Err(format!("unknown operator type `{}`", other)),
+    }
+}
+
+fn lookup_op(name: &str) -> OpDef {
+    OPERATORS.with(|ops| ops.borrow().get(name).cloned().unwrap_or_default())
+}
+
+/// Register (or override) an operator, as the `op/3` directive does.
+pub fn register_op(priority: u32, ty: &str, name: &str) -> Result<(), String> {
+    let ty = parse_optype(ty)?;
+    OPERATORS.with(|ops| {
+        let mut ops = ops.borrow_mut();
+        let def = ops.entry(name.to_string()).or_insert_with(OpDef::default);
+        match ty {
+            OpType::Fy | OpType::Fx => def.prefix = Some((priority, ty)),
+            _ => def.infix = Some((priority, ty)),
+        }
+    });
+    Ok(())
+}
+
+/// A `Peekable` char stream that remembers how far it has advanced, so every
+/// parse function can attach a char offset to the diagnostics it raises. It
+/// also holds a one-token pushback for an operator the precedence parser read
+/// but deferred to an outer priority level.
+struct Source<I: Iterator<Item = char>> {
+    iter: Peekable<I>,
+    pos: usize,
+    pending_op: Option<String>,
+    // Variables seen so far in the command currently being parsed, keyed by
+    // name, so repeated occurrences (including across a clause head and
+    // body) resolve to the same `Variable` instead of each minting a fresh
+    // one. Reset between top-level commands by `parse_command`.
+    scope: HashMap<String, Variable>,
+}
+
+impl<I: Iterator<Item = char>> Source<I> {
+    fn new(iter: I) -> Self {
+        Source {
+            iter: iter.peekable(),
+            pos: 0,
+            pending_op: None,
+            scope: HashMap::new(),
+        }
+    }
+
+    // Intern `name` against the current command's scope: the first mention
+    // of a name mints a fresh variable, later mentions co-refer with it.
+    fn variable(&mut self, name: String) -> Variable {
+        self.scope
+            .entry(name.clone())
+            .or_insert_with(|| Variable::brand_new(name))
+            .clone()
+    }
+
+    fn reset_scope(&mut self) {
+        self.scope.clear();
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.iter.peek()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.iter.next();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn pos(&self) -> usize {
+        self.pos
+    }
 }
 
 /* Parser
- * query := assertion | question
+ * program := (assertion | question | directive)*
  * question := ?- term '.'
+ * directive := ':-' term '.'
  * assertion := clause '.'
- * clause := term [':-' term (',' term)* ]
- * term := atom | variable | list
+ * clause := predicate [':-' term (',' term)* ]
+ * term := precedence-climbed expression over primaries
+ * primary := '(' term ')' | prefix-op term | list | atom | variable | number
+ * list := '[' ']' | '[' term (',' term)* ['|' term] ']'
  * atom := <lowercase> <id_char>*
- * variable := <uppercase> <id_char>*
+ * variable := (<uppercase> | '_') <id_char>*
  */
-fn consume_spaces<I: Iterator<Item = char>>(iter: &mut Peekable<I>) {
+fn consume_spaces<I: Iterator<Item = char>>(iter: &mut Source<I>) {
     loop {
         // kill the reference
         if let Some(c) = iter.peek().map(|x| *x) {
@@ -39,7 +209,13 @@ fn identifier_character(c: char) -> bool {
     c.is_alphabetic() || c.is_numeric() || c == '_' || c == '-'
 }
 
-fn identifier<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> String {
+// The characters an operator name is built from (the clause terminator `.` and
+// the argument separator `,` are deliberately excluded).
+fn symbol_character(c: char) -> bool {
+    "+-*/\\^<>=~:?@#&$".contains(c)
+}
+
+fn identifier<I: Iterator<Item = char>>(iter: &mut Source<I>) -> String {
     consume_spaces(iter);
     let mut s = String::new();
     loop {
@@ -53,52 +229,206 @@ fn identifier<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> String {
     }
 }
 
-fn atom<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> Result<Atom, ParseError> {
+fn atom<I: Iterator<Item = char>>(iter: &mut Source<I>) -> Result<Atom, ParseError> {
     Ok(Atom::new(identifier(iter)))
 }
 
-fn variable<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> Result<Variable, ParseError> {
-    Ok(Variable::new(identifier(iter)))
+fn variable<I: Iterator<Item = char>>(iter: &mut Source<I>) -> Result<Variable, ParseError> {
+    let name = identifier(iter);
+    Ok(iter.variable(name))
+}
+
+fn number<I: Iterator<Item = char>>(iter: &mut Source<I>) -> ParseResult {
+    let start = iter.pos();
+    let mut s = String::new();
+    while let Some(&c) = iter.peek() {
+        if c.is_numeric() {
+            iter.next();
+            s.push(c);
+        } else {
+            break;
+        }
+    }
+    match s.parse::<i64>() {
+        Ok(n) => Ok(Term::Num(n)),
+        Err(_) => Err(invalid((start, iter.pos()), "invalid number")),
+    }
+}
+
+// Read the next operator token, honouring a pushed-back token from a deferred
+// priority level. Symbolic operators are a maximal run of symbol characters;
+// alphabetic operators (e.g. `is`, `mod`) are ordinary identifiers.
+fn read_operator<I: Iterator<Item = char>>(iter: &mut Source<I>) -> Option<String> {
+    if let Some(op) = iter.pending_op.take() {
+        return Some(op);
+    }
+    consume_spaces(iter);
+    match iter.peek().map(|c| *c) {
+        // `,` is an operator, but only above functor-argument priority (999),
+        // so in argument position the precedence climb hands it back and
+        // `arguments_impl` picks it up as a separator.
+        Some(',') => {
+            iter.next();
+            Some(",".to_string())
+        }
+        Some(c) if symbol_character(c) => {
+            let mut s = String::new();
+            while let Some(&c) = iter.peek() {
+                if symbol_character(c) {
+                    iter.next();
+                    s.push(c);
+                } else {
+                    break;
+                }
+            }
+            Some(s)
+        }
+        Some(c) if c.is_lowercase() => Some(identifier(iter)),
+        _ => None,
+    }
+}
+
+fn cons2(name: String, left: Term, right: Term) -> Term {
+    Term::Pred(Predicate {
+        name: Atom::new(name),
+        arguments: List::Cons(
+            Box::new(left),
+            Box::new(List::Cons(Box::new(right), Box::new(List::Nil))),
+        ),
+    })
+}
+
+fn cons1(name: String, operand: Term) -> Term {
+    Term::Pred(Predicate {
+        name: Atom::new(name),
+        arguments: List::Cons(Box::new(operand), Box::new(List::Nil)),
+    })
+}
+
+// Proper Prolog lists are the standard `'.'(Head, Tail)` cons cells terminated
+// by the `[]` atom, kept distinct from the predicate-argument `List`.
+fn empty_list() -> Term {
+    Term::Pred(Predicate {
+        name: Atom::new("[]".to_string()),
+        arguments: List::Nil,
+    })
+}
+
+fn build_list(elements: Vec<Term>, tail: Term) -> Term {
+    let mut result = tail;
+    for element in elements.into_iter().rev() {
+        result = cons2(".".to_string(), element, result);
+    }
+    result
+}
+
+// '['は既に読まれている
+fn list<I: Iterator<Item = char>>(iter: &mut Source<I>) -> ParseResult {
+    consume_spaces(iter);
+    if let Some(&']') = iter.peek() {
+        iter.next();
+        return Ok(empty_list());
+    }
+
+    let mut elements = vec![];
+    loop {
+        elements.push(argument(iter)?);
+
+        // the precedence climb reads the separating comma and hands it back
+        if iter.pending_op.as_ref().map_or(false, |s| s == ",") {
+            iter.pending_op = None;
+            continue;
+        }
+        consume_spaces(iter);
+        match iter.peek() {
+            Some(&',') => {
+                iter.next();
+            }
+            Some(&'|') => {
+                iter.next();
+                let tail = argument(iter)?;
+                consume_spaces(iter);
+                return match iter.peek() {
+                    Some(&']') => {
+                        iter.next();
+                        Ok(build_list(elements, tail))
+                    }
+                    None => Err(ParseError::Incomplete),
+                    _ => {
+                        let at = iter.pos();
+                        Err(invalid((at, at + 1), "expected `]`"))
+                    }
+                };
+            }
+            Some(&']') => {
+                iter.next();
+                return Ok(build_list(elements, empty_list()));
+            }
+            None => return Err(ParseError::Incomplete),
+            _ => {
+                let at = iter.pos();
+                return Err(invalid((at, at + 1), "expected `,`, `|` or `]`"));
+            }
+        }
+    }
 }
 
 fn arguments_impl<I: Iterator<Item = char>>(
-    iter: &mut Peekable<I>,
+    iter: &mut Source<I>,
     end: char,
 ) -> Result<List, ParseError> {
     consume_spaces(iter);
+    // the precedence parser reads the separating comma and hands it back here
+    if iter.pending_op.as_ref().map_or(false, |s| s == ",") {
+        iter.pending_op = None;
+        let arg = argument(iter)?;
+        return arguments_impl(iter, end).map(|args| List::Cons(Box::new(arg), Box::new(args)));
+    }
     match iter.peek() {
-        None => Err(()),
+        None => Err(ParseError::Incomplete),
         Some(&c) if c == end => {
             iter.next();
             Ok(List::Nil)
         }
         Some(&',') => {
             iter.next();
-            let arg = term(iter)?;
+            let arg = argument(iter)?;
             arguments_impl(iter, end).map(|args| List::Cons(Box::new(arg), Box::new(args)))
         }
-        _ => Err(()),
+        _ => {
+            let at = iter.pos();
+            Err(invalid((at, at + 1), &format!("expected `{}`", end)))
+        }
     }
 }
 
 // ')'も読む
 fn arguments<I: Iterator<Item = char>>(
-    iter: &mut Peekable<I>,
+    iter: &mut Source<I>,
     end: char,
 ) -> Result<List, ParseError> {
     consume_spaces(iter);
     match iter.peek() {
-        None => Err(()),
+        None => Err(ParseError::Incomplete),
         _ => {
-            let first = term(iter)?;
+            let first = argument(iter)?;
             arguments_impl(iter, end).map(|args| List::Cons(Box::new(first), Box::new(args)))
         }
     }
 }
 
-fn predicate<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> Result<Predicate, ParseError> {
+fn predicate<I: Iterator<Item = char>>(iter: &mut Source<I>) -> Result<Predicate, ParseError> {
     consume_spaces(iter);
+    let start = iter.pos();
     let p = atom(iter)?;
+    if let Some(c) = p.name.chars().next() {
+        if c.is_uppercase() {
+            return Err(invalid(
+                (start, iter.pos()),
+                "unexpected uppercase in predicate name",
+            ));
+        }
+    }
 
     consume_spaces(iter);
     match iter.peek() {
@@ -118,62 +448,295 @@ fn predicate<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> Result<Predica
     }
 }
 
-fn term<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> ParseResult {
+// A primary: a parenthesised term, a prefix-operator application, or one of
+// the atomic terms.
+fn primary<I: Iterator<Item = char>>(iter: &mut Source<I>) -> ParseResult {
     consume_spaces(iter);
-
-    // kill the reference
+    let at = iter.pos();
     match iter.peek().map(|x| *x) {
-        None => Err(()),
+        None => Err(ParseError::Incomplete),
+        Some('(') => {
+            iter.next();
+            let inner = expr(iter, 1200)?;
+            consume_spaces(iter);
+            match iter.peek() {
+                Some(&')') => {
+                    iter.next();
+                    Ok(inner)
+                }
+                None => Err(ParseError::Incomplete),
+                _ => {
+                    let p = iter.pos();
+                    Err(invalid((p, p + 1), "expected `)`"))
+                }
+            }
+        }
+        Some('[') => {
+            iter.next();
+            list(iter)
+        }
+        Some(c) if symbol_character(c) => {
+            let name = read_operator(iter).unwrap();
+            match lookup_op(&name).prefix {
+                Some((priority, ty)) => {
+                    let arg_max = match ty {
+                        OpType::Fy => priority,
+                        _ => priority - 1,
+                    };
+                    let operand = expr(iter, arg_max)?;
+                    Ok(cons1(name, operand))
+                }
+                None => Err(invalid(
+                    (at, iter.pos()),
+                    &format!("`{}` is not a prefix operator", name),
+                )),
+            }
+        }
         Some(c) => {
             if c.is_lowercase() {
                 predicate(iter).map(Term::Pred)
             } else if c.is_uppercase() {
                 Ok(Term::Var(variable(iter)?))
+            } else if c == '_' {
+                // `_` (and any `_`-prefixed name) is the anonymous variable:
+                // every occurrence is a fresh variable, never interned by
+                // name, so it never co-refers with another `_`.
+                Ok(Term::Var(Variable::brand_new(identifier(iter))))
+            } else if c.is_numeric() {
+                number(iter)
             } else {
-                Err(())
+                Err(invalid((at, at + 1), &format!("expected a term, found `{}`", c)))
+            }
+        }
+    }
+}
+
+// Precedence-climbing parser: parse a primary, then fold in every infix
+// operator whose priority is within `max`, recursing with the right-hand
+// priority its associativity permits.
+fn expr<I: Iterator<Item = char>>(iter: &mut Source<I>, max: u32) -> ParseResult {
+    let mut left = primary(iter)?;
+    loop {
+        let name = match read_operator(iter) {
+            Some(name) => name,
+            None => break,
+        };
+        match lookup_op(&name).infix {
+            Some((priority, ty)) if priority <= max => {
+                let right_max = match ty {
+                    OpType::Xfy => priority,
+                    _ => priority - 1,
+                };
+                let right = expr(iter, right_max)?;
+                left = cons2(name, left, right);
+            }
+            _ => {
+                // not usable at this level: hand it back to an outer climb
+                iter.pending_op = Some(name);
+                break;
             }
         }
     }
+    Ok(left)
+}
+
+// Arguments of a functor bind tighter than `,` (priority 999).
+fn argument<I: Iterator<Item = char>>(iter: &mut Source<I>) -> ParseResult {
+    expr(iter, 999)
+}
+
+fn term<I: Iterator<Item = char>>(iter: &mut Source<I>) -> ParseResult {
+    expr(iter, 1200)
 }
 
-fn clause<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> ParseResult {
-    let result = predicate(iter)?;
+fn clause<I: Iterator<Item = char>>(iter: &mut Source<I>) -> ParseResult {
+    let start = iter.pos();
+    // A head binds no looser than a functor argument (999), so it parses
+    // through the same operator-aware climb as any other term instead of
+    // being restricted to bare `name(args)` syntax.
+    let head = argument(iter)?;
+    let result = match head {
+        Term::Pred(p) => p,
+        other => {
+            return Err(invalid(
+                (start, iter.pos()),
+                &format!("expected a callable clause head, found `{}`", other),
+            ))
+        }
+    };
+    // the head's precedence climb reads `:-` (priority 1200) and hands it back
+    if iter.pending_op.as_ref().map_or(false, |s| s == ":-") {
+        iter.pending_op = None;
+        let conditions = arguments(iter, '.')?;
+        return Ok(Term::Clause(Clause {
+            result: result,
+            conditions: conditions,
+        }));
+    }
     consume_spaces(iter);
+    let at = iter.pos();
     match iter.peek() {
-        Some(&'.') => Ok(Term::Pred(result)),
+        Some(&'.') => {
+            iter.next();
+            Ok(Term::Pred(result))
+        }
         Some(&':') => {
             iter.next();
-            if let Some('-') = iter.next() {
-                let conditions = arguments(iter, '.')?;
-                Ok(Term::Clause(Clause {
-                    result: result,
-                    conditions: conditions,
-                }))
-            } else {
-                Err(())
+            match iter.next() {
+                Some('-') => {
+                    let conditions = arguments(iter, '.')?;
+                    Ok(Term::Clause(Clause {
+                        result: result,
+                        conditions: conditions,
+                    }))
+                }
+                Some(_) => Err(invalid((at, iter.pos()), "expected `-` after `:`")),
+                None => Err(ParseError::Incomplete),
             }
         }
-        _ => Err(()),
+        None => Err(ParseError::Incomplete),
+        _ => Err(invalid((start, at + 1), "unterminated clause")),
     }
 }
 
-pub fn parse_line<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> Result<Command, ParseError> {
+// Read `term '.'`, used by both questions and directives.
+fn goal_to_dot<I: Iterator<Item = char>>(iter: &mut Source<I>) -> ParseResult {
+    let g = term(iter)?;
     consume_spaces(iter);
+    let dot = iter.pos();
+    match iter.peek() {
+        Some(&'.') => {
+            iter.next();
+            Ok(g)
+        }
+        None => Err(ParseError::Incomplete),
+        _ => Err(invalid((dot, dot + 1), "expected `.`")),
+    }
+}
 
+fn parse_command<I: Iterator<Item = char>>(iter: &mut Source<I>) -> Result<Command, ParseError> {
+    // Each command (assertion, question, or directive) is its own variable
+    // scope: names are interned within it so repeated occurrences co-refer,
+    // but an `X` in one clause never aliases an `X` in the next.
+    iter.reset_scope();
+    let at = iter.pos();
     match iter.peek() {
         Some(&'?') => {
             iter.next();
-            if let Some('-') = iter.next() {
-                let q = term(iter)?;
-                consume_spaces(iter);
-                if let Some(&'.') = iter.peek() {
-                    iter.next();
-                    return Ok(Command::Question(q));
-                }
+            match iter.next() {
+                Some('-') => {}
+                Some(_) => return Err(invalid((at, iter.pos()), "expected `-` after `?`")),
+                None => return Err(ParseError::Incomplete),
+            }
+            goal_to_dot(iter).map(Command::Question)
+        }
+        Some(&':') => {
+            iter.next();
+            match iter.next() {
+                Some('-') => {}
+                Some(_) => return Err(invalid((at, iter.pos()), "expected `-` after `:`")),
+                None => return Err(ParseError::Incomplete),
             }
-            Err(())
+            goal_to_dot(iter).map(Command::Directive)
         }
         Some(_) => clause(iter).map(Command::Assertion),
-        _ => Err(()),
+        None => Err(ParseError::Incomplete),
+    }
+}
+
+/// Parse every complete command in the buffer.
+///
+/// `Ok` is returned only once the whole buffer has been consumed into zero or
+/// more commands; `Err(Incomplete)` means the tail of the buffer is a partial
+/// term and the caller should read more input before retrying.
+pub fn parse_terms<I: Iterator<Item = char>>(iter: I) -> Result<Vec<Command>, ParseError> {
+    let mut src = Source::new(iter);
+    let mut commands = vec![];
+    loop {
+        consume_spaces(&mut src);
+        if src.peek().is_none() {
+            return Ok(commands);
+        }
+        commands.push(parse_command(&mut src)?);
+    }
+}
+
+/// Parse every command in `source`, one clause at a time, recovering from an
+/// invalid one instead of discarding everything after it: on failure, skip
+/// ahead to the next top-level `.` (there is no other use of `.` in this
+/// grammar) and resume there. A trailing unterminated fragment is reported
+/// once, at end of file, exactly like `parse_terms`'s `Incomplete`.
+pub fn parse_commands(source: &str) -> Vec<Result<Command, Diagnostic>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut offset = 0;
+    let mut results = vec![];
+    loop {
+        let mut src = Source::new(chars[offset..].iter().cloned());
+        consume_spaces(&mut src);
+        if src.peek().is_none() {
+            return results;
+        }
+        match parse_command(&mut src) {
+            Ok(command) => {
+                offset += src.pos();
+                results.push(Ok(command));
+            }
+            Err(ParseError::Invalid(diag)) => {
+                let span = (offset + diag.span.0, offset + diag.span.1);
+                let resume_from = span.1;
+                results.push(Err(Diagnostic {
+                    message: diag.message,
+                    span: span,
+                }));
+                match chars[resume_from.min(chars.len())..].iter().position(|&c| c == '.') {
+                    Some(rel) => offset = resume_from + rel + 1,
+                    None => return results,
+                }
+            }
+            Err(ParseError::Incomplete) => {
+                let at = offset + src.pos();
+                results.push(Err(Diagnostic {
+                    message: "unexpected end of file in an unterminated clause".into(),
+                    span: (at, at),
+                }));
+                return results;
+            }
+        }
+    }
+}
+
+/// Render a `Diagnostic` against its source as a caret-underlined snippet:
+/// the offending line, then a line of spaces and `^` marks under the span
+/// followed by the message.
+pub fn render_error(source: &str, diag: &Diagnostic) -> String {
+    let (start, end) = diag.span;
+    let chars: Vec<char> = source.chars().collect();
+
+    let mut line_start = 0;
+    for i in 0..start.min(chars.len()) {
+        if chars[i] == '\n' {
+            line_start = i + 1;
+        }
+    }
+    let mut line_end = line_start;
+    while line_end < chars.len() && chars[line_end] != '\n' {
+        line_end += 1;
+    }
+
+    let line: String = chars[line_start..line_end].iter().collect();
+    let column = start.saturating_sub(line_start);
+    let width = end.saturating_sub(start).max(1);
+
+    let mut out = String::new();
+    out.push_str(&line);
+    out.push('\n');
+    for _ in 0..column {
+        out.push(' ');
+    }
+    for _ in 0..width {
+        out.push('^');
     }
+    out.push(' ');
+    out.push_str(&diag.message);
+    out
 }
@@ -29,6 +29,20 @@ fn get_debug() -> bool {
     DEBUG.with(|d| *d.borrow())
 }
 
+thread_local!{
+    // When on (the default), binding a variable to a term that contains it is
+    // rejected; turning it off allows the unsound-but-fast rational-tree mode.
+    static OCCURS_CHECK: RefCell<bool> = RefCell::new(true);
+}
+
+pub fn set_occurs_check(b: bool) {
+    OCCURS_CHECK.with(|c| *c.borrow_mut() = b);
+}
+
+fn get_occurs_check() -> bool {
+    OCCURS_CHECK.with(|c| *c.borrow())
+}
+
 fn shift() {
     LEVEL.with(|l| *l.borrow_mut() += 1);
 }
@@ -41,6 +55,34 @@ fn get_level() -> usize {
     LEVEL.with(|l| *l.borrow())
 }
 
+thread_local!{
+    // Every variable cell bound during derivation is recorded here so that
+    // backtracking can reset it to `None`, exactly like the shared debug state
+    // above.
+    static TRAIL: RefCell<Vec<Rc<RefCell<Option<Term>>>>> = RefCell::new(vec![]);
+}
+
+// The current length of the trail; pass it to `undo_to` to roll every binding
+// made afterwards back to unbound.
+pub fn trail_mark() -> usize {
+    TRAIL.with(|t| t.borrow().len())
+}
+
+fn trail_push(cell: Rc<RefCell<Option<Term>>>) {
+    TRAIL.with(|t| t.borrow_mut().push(cell));
+}
+
+pub fn undo_to(mark: usize) {
+    TRAIL.with(|t| {
+        let mut trail = t.borrow_mut();
+        while trail.len() > mark {
+            if let Some(cell) = trail.pop() {
+                *cell.borrow_mut() = None;
+            }
+        }
+    });
+}
+
 macro_rules! debug_println {
     ($( $arg:expr ),*) => { 
         if get_debug() { 
@@ -89,10 +131,6 @@ impl Hash for Variable {
 }
 
 impl Variable {
-    pub fn new(name: String, id: usize) -> Self {
-        Variable { name: name, id: id, assignment: Rc::new(RefCell::new(None)) }
-    }
-
     pub fn brand_new(name: String) -> Self {
         Variable {
             name: name,
@@ -115,7 +153,12 @@ impl Variable {
     pub fn assign(&mut self, mut term: Term) -> Result<(), DeriveError> {
         let assignment =
             match &mut *self.assignment.borrow_mut() {
-                &mut None => Some(term),
+                &mut None => {
+                    if get_occurs_check() && occurs(self, &term, &mut HashSet::new()) {
+                        return Err("occurs check failed".into());
+                    }
+                    Some(term)
+                }
                 &mut Some(ref mut other) => {
                     other.unify(&mut term)?;
                     None
@@ -123,31 +166,33 @@ impl Variable {
             };
         if let Some(term) = assignment {
             *self.assignment.borrow_mut() = Some(term);
+            trail_push(self.assignment.clone());
         }
-        self.compress();
         debug_println!("assign {} <= {}", self, self.assignment.borrow().as_ref().unwrap());
         Ok(())
     }
+}
 
-    fn compress(&self) {
-        use Term::*;
-        let assignment = 
-            if let &mut Some(ref mut term) = &mut *self.assignment.borrow_mut() {
-                match *term {
-                    Var(ref mut v) => {
-                        v.compress();
-                        if let &mut Some(ref t) = &mut *v.assignment.borrow_mut() {
-                            t.clone()
-                        } else {
-                            return;
-                        }
-                    },
-                    _ => return,
-                }
-            } else {
-                return;
-            };
-        *self.assignment.borrow_mut() = Some(assignment);
+// Does `var` occur anywhere in `term`, following already-bound variables?
+// `visited` bounds the walk over shared/cyclic structure.
+fn occurs(var: &Variable, term: &Term, visited: &mut HashSet<usize>) -> bool {
+    use Term::*;
+    match *term {
+        Var(ref v) => {
+            if v.id == var.id {
+                return true;
+            }
+            if !visited.insert(v.id) {
+                return false;
+            }
+            let bound = v.assignment.borrow().clone();
+            match bound {
+                Some(ref t) => occurs(var, t, visited),
+                None => false,
+            }
+        }
+        Pred(ref p) => p.arguments.to_vec().iter().any(|t| occurs(var, t, visited)),
+        Num(_) | Clause(_) => false,
     }
 }
 
@@ -174,6 +219,14 @@ pub struct Clause {
 }
 
 impl Clause {
+    pub fn variables(&self) -> Variables {
+        let mut ret = self.result.variables();
+        for v in self.conditions.variables().into_iter() {
+            ret.insert(v);
+        }
+        ret
+    }
+
     pub fn instantiate(&self, dict: &mut HashMap<Variable, Variable>) -> Self {
         Clause {
             result: self.result.instantiate(dict),
@@ -193,6 +246,10 @@ impl List {
         ListIterator(self)
     }
 
+    pub fn to_vec(&self) -> Vec<Term> {
+        self.iter().cloned().collect()
+    }
+
     pub fn instantiate(&self, dict: &mut HashMap<Variable, Variable>) -> Self {
         use List::*;
         match self {
@@ -228,7 +285,8 @@ impl<'a> Iterator for ListIterator<'a> {
 pub enum Term {
     Var(Variable),
     Pred(Predicate),
-    List(List),
+    Num(i64),
+    Clause(Clause),
 }
 
 type Variables = HashSet<Variable>;
@@ -239,32 +297,58 @@ impl Predicate {
         self.arguments.variables()
     }
 
-    pub fn derive(&self, knowledge: &[Clause]) -> Result<Variables, DeriveError> {
+    // Lazily enumerate every way this predicate can be proved, with `rest`
+    // the goals still to satisfy afterwards. `found` is invoked on each full
+    // solution; it returns `true` to stop the search. The return value is that
+    // stop signal, propagated back up.
+    fn solve(&self, rest: &[Term], knowledge: &[Clause], found: &mut FnMut() -> bool) -> bool {
         debug_println!("derive {}", self);
+
+        // built-in predicates are evaluated instead of matched against facts
+        if let Some(result) = self.derive_builtin() {
+            return match result {
+                Ok(_) => solve(rest, knowledge, found),
+                Err(_) => false,
+            };
+        }
+
         shift();
-        for mut fact in knowledge.iter().map(|c| c.instantiate(&mut HashMap::new())) {
+        for clause in knowledge.iter() {
+            let mut fact = clause.instantiate(&mut HashMap::new());
+            // remember where the trail was so we can undo this clause's bindings
+            let mark = trail_mark();
             let mut target = self.clone();
-            // this changes the shared state of variables within self
-            // so we need to some reset
             if let Ok(()) = target.unify(&mut fact.result) {
-                // discard the variables in conditions 
-                // because only the top level variables will be returned
-                if let Ok(_) = fact.conditions.derive(knowledge) {
+                // prepend the clause body to the remaining conjunction
+                let mut goals: Vec<Term> = fact.conditions.iter().cloned().collect();
+                goals.extend_from_slice(rest);
+                if solve(&goals, knowledge, found) {
                     unshift();
-                    let vs = target.variables();
-                    for v in vs.iter() {
-                        v.compress();
-                    }
-                    return Ok(vs);
+                    return true;
                 }
             }
+            undo_to(mark);
         }
         unshift();
-        Err("No matching facts".into())
+        false
+    }
+
+    // `is/2` and the arithmetic comparisons are evaluated directly instead of
+    // being matched against facts. Returns `None` for an ordinary predicate.
+    fn derive_builtin(&self) -> Option<Result<Variables, DeriveError>> {
+        let args: Vec<&Term> = self.arguments.iter().collect();
+        match (self.name.name.as_str(), args.len()) {
+            ("is", 2) => Some(eval_is(args[0], args[1])),
+            ("=", 2) => Some(unify_goal(args[0], args[1])),
+            ("<", 2) | (">", 2) | ("=:=", 2) => {
+                Some(eval_compare(self.name.name.as_str(), args[0], args[1]))
+            }
+            _ => None,
+        }
     }
 
     fn unify(&mut self, other: &mut Self) -> Result<(), DeriveError> {
-        debug_println!("PREDICATE: self = {}, other = {}", self, other); 
+        debug_println!("PREDICATE: self = {}, other = {}", self, other);
 
         use List::*;
         if self.name != other.name {
@@ -289,6 +373,130 @@ impl Predicate {
     }
 }
 
+// Evaluate an arithmetic expression down to a machine integer, following the
+// same structural recursion over the operator tree as unification does over
+// terms. Every leaf must be ground.
+fn eval(term: &Term) -> Result<i64, DeriveError> {
+    use Term::*;
+    match *term {
+        Num(n) => Ok(n),
+        Var(ref v) => {
+            let assignment = v.assignment.borrow().clone();
+            match assignment {
+                Some(t) => eval(&t),
+                None => Err("arguments not sufficiently instantiated".into()),
+            }
+        }
+        Pred(ref p) => {
+            let operands: Vec<&Term> = p.arguments.iter().collect();
+            match (p.name.name.as_str(), operands.len()) {
+                ("-", 1) => eval(operands[0])?.checked_neg().ok_or_else(|| "overflow".into()),
+                ("+", 1) => eval(operands[0]),
+                ("+", 2) => {
+                    let (a, b) = (eval(operands[0])?, eval(operands[1])?);
+                    a.checked_add(b).ok_or_else(|| "overflow".into())
+                }
+                ("-", 2) => {
+                    let (a, b) = (eval(operands[0])?, eval(operands[1])?);
+                    a.checked_sub(b).ok_or_else(|| "overflow".into())
+                }
+                ("*", 2) => {
+                    let (a, b) = (eval(operands[0])?, eval(operands[1])?);
+                    a.checked_mul(b).ok_or_else(|| "overflow".into())
+                }
+                ("/", 2) => {
+                    let (a, b) = (eval(operands[0])?, eval(operands[1])?);
+                    if b == 0 {
+                        Err("zero divisor".into())
+                    } else {
+                        a.checked_div(b).ok_or_else(|| "overflow".into())
+                    }
+                }
+                ("mod", 2) => {
+                    let (a, b) = (eval(operands[0])?, eval(operands[1])?);
+                    if b == 0 {
+                        Err("zero divisor".into())
+                    } else {
+                        a.checked_rem(b).ok_or_else(|| "overflow".into())
+                    }
+                }
+                _ => Err(format!("{} is not an evaluable functor", p.name)),
+            }
+        }
+        Clause(_) => Err("a clause is not evaluable".into()),
+    }
+}
+
+/// Prove the conjunction `goals` against `knowledge`, invoking `found` once
+/// per solution with every binding live on the trail. `found` returns `true`
+/// to stop; `solve` returns whether the search was stopped that way.
+pub fn solve(goals: &[Term], knowledge: &[Clause], found: &mut FnMut() -> bool) -> bool {
+    match goals.split_first() {
+        // an empty conjunction is trivially true: one solution
+        None => found(),
+        Some((goal, rest)) => solve_goal(goal, rest, knowledge, found),
+    }
+}
+
+fn solve_goal(
+    goal: &Term,
+    rest: &[Term],
+    knowledge: &[Clause],
+    found: &mut FnMut() -> bool,
+) -> bool {
+    use Term::*;
+    match *goal {
+        // `,`/2 is the conjunction connective: splice both sides into the goals
+        Pred(ref p) if p.name.name == "," && p.arguments.to_vec().len() == 2 => {
+            let mut goals = p.arguments.to_vec();
+            goals.extend_from_slice(rest);
+            solve(&goals, knowledge, found)
+        }
+        Pred(ref p) => p.solve(rest, knowledge, found),
+        // a variable goal, bound or not, succeeds without being called
+        Var(_) => solve(rest, knowledge, found),
+        Num(_) | Clause(_) => false,
+    }
+}
+
+// `=(A, B)`: unify the two terms and report the variables involved.
+fn unify_goal(lhs: &Term, rhs: &Term) -> Result<Variables, DeriveError> {
+    let mut a = lhs.clone();
+    let mut b = rhs.clone();
+    a.unify(&mut b)?;
+    let mut vs = a.variables();
+    for v in b.variables().into_iter() {
+        vs.insert(v);
+    }
+    Ok(vs)
+}
+
+// `is(X, Expr)`: evaluate `Expr` and unify the integer result with `X`.
+fn eval_is(x: &Term, expr: &Term) -> Result<Variables, DeriveError> {
+    let value = eval(expr)?;
+    let mut x = x.clone();
+    x.unify(&mut Term::Num(value))?;
+    Ok(x.variables())
+}
+
+// Arithmetic comparison: evaluate both sides and succeed or fail with no
+// binding left behind.
+fn eval_compare(op: &str, lhs: &Term, rhs: &Term) -> Result<Variables, DeriveError> {
+    let a = eval(lhs)?;
+    let b = eval(rhs)?;
+    let holds = match op {
+        "<" => a < b,
+        ">" => a > b,
+        "=:=" => a == b,
+        _ => return Err(format!("{} is not a comparison operator", op)),
+    };
+    if holds {
+        Ok(HashSet::new())
+    } else {
+        Err(format!("{} {} {} does not hold", a, op, b))
+    }
+}
+
 impl Term {
     pub fn variables(&self) -> Variables {
         use Term::*;
@@ -300,21 +508,8 @@ impl Term {
                 ret
             },
             Pred(ref p) => p.variables(),
-            List(ref l) => l.variables(),
-        }
-    }
-
-    pub fn derive(&self, knowledge: &[Clause]) -> Result<Variables, DeriveError> {
-        use Term::*;
-        match self {
-            &Var(ref v) => {
-                // anything can be derived
-                let mut ret = HashSet::new();
-                ret.insert(v.clone());
-                Ok(ret)
-            },
-            &Pred(ref pred) => pred.derive(knowledge),
-            &List(ref list) => list.derive(knowledge),
+            Num(_) => HashSet::new(),
+            Clause(ref c) => c.variables(),
         }
     }
 
@@ -323,7 +518,8 @@ impl Term {
         match self {
             &Var(ref v) => Var(v.instantiate(dict)),
             &Pred(ref p) => Pred(p.instantiate(dict)),
-            &List(ref l) => List(l.instantiate(dict)),
+            &Num(n) => Num(n),
+            &Clause(ref c) => Clause(c.instantiate(dict)),
         }
     }
 
@@ -332,16 +528,14 @@ impl Term {
         use Term::*;
 
         match (self, other) {
-            (&mut Var(ref mut v), ref mut o) => {
-                // TODO: need occurs check
-                v.assign(o.clone())
-            },
-            (ref mut this, &mut Var(ref mut v)) => {
-                // TODO: need occurs check
-                v.assign(this.clone())
-            },
+            (&mut Var(ref mut v), ref mut o) => v.assign(o.clone()),
+            (ref mut this, &mut Var(ref mut v)) => v.assign(this.clone()),
             (&mut Pred(ref mut this), &mut Pred(ref mut o)) => this.unify(o),
-            (&mut List(ref mut this), &mut List(ref mut o)) => this.unify(o),
+            (&mut Num(a), &mut Num(b)) => if a == b {
+                Ok(())
+            } else {
+                Err("Number mismatch".into())
+            },
             _ => Err("Term type doesn't match".into()),
         }
     }
@@ -375,20 +569,5 @@ impl List {
             _ => Err("List size doesn't match".into())
         }
     }
-
-    // derivation of a list means derivation of the conjunction of each element
-    pub fn derive(&self, knowledge: &[Clause]) -> Result<Variables, DeriveError> {
-        use List::*;
-        match self {
-            &Nil => Ok(HashSet::new()),
-            &Cons(ref head, ref tail) => {
-                let mut ret = head.derive(knowledge)?;
-                for v in tail.derive(knowledge)?.into_iter() {
-                    ret.insert(v);
-                }
-                Ok(ret)
-            }
-        }
-    }
 }
 